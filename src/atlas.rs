@@ -0,0 +1,100 @@
+//! A simple shelf/skyline bin-packer for composing many small textures into
+//! one atlas, the way engines consume sprite sheets as a single GPU texture.
+
+#[derive(Clone, Copy)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PackError {
+    #[error("texture {index} is {width}px wide, which exceeds the atlas width {atlas_width}px")]
+    TextureWiderThanAtlas {
+        index: usize,
+        width: u32,
+        atlas_width: u32,
+    },
+}
+
+/// Packs `sizes` into shelves no wider than `atlas_width`, tallest-first,
+/// and returns each input's assigned rect alongside the total atlas height.
+pub fn pack(sizes: &[(u32, u32)], atlas_width: u32) -> Result<(Vec<Rect>, u32), PackError> {
+    if let Some((index, &(width, _))) = sizes.iter().enumerate().find(|(_, &(width, _))| width > atlas_width) {
+        return Err(PackError::TextureWiderThanAtlas {
+            index,
+            width,
+            atlas_width,
+        });
+    }
+
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by(|&a, &b| sizes[b].1.cmp(&sizes[a].1));
+
+    let mut rects = vec![
+        Rect {
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+        };
+        sizes.len()
+    ];
+    let mut shelf_x = 0u32;
+    let mut shelf_y = 0u32;
+    let mut shelf_height = 0u32;
+    for index in order {
+        let (width, height) = sizes[index];
+        if shelf_x > 0 && shelf_x + width > atlas_width {
+            shelf_y += shelf_height;
+            shelf_x = 0;
+            shelf_height = 0;
+        }
+        rects[index] = Rect {
+            x: shelf_x,
+            y: shelf_y,
+            width,
+            height,
+        };
+        shelf_x += width;
+        shelf_height = shelf_height.max(height);
+    }
+
+    Ok((rects, shelf_y + shelf_height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_fits_exact_shelf_width() {
+        let (rects, height) = pack(&[(10, 5), (10, 5)], 20).unwrap();
+        assert_eq!(rects[0].x, 0);
+        assert_eq!(rects[1].x, 10);
+        assert_eq!(height, 5);
+    }
+
+    #[test]
+    fn pack_overflows_to_a_new_shelf() {
+        let (rects, height) = pack(&[(15, 5), (15, 5)], 20).unwrap();
+        assert_eq!((rects[0].x, rects[0].y), (0, 0));
+        assert_eq!((rects[1].x, rects[1].y), (0, 5));
+        assert_eq!(height, 10);
+    }
+
+    #[test]
+    fn pack_rejects_a_texture_wider_than_the_atlas() {
+        let error = pack(&[(25, 5)], 20).unwrap_err();
+        assert!(matches!(
+            error,
+            PackError::TextureWiderThanAtlas {
+                index: 0,
+                width: 25,
+                atlas_width: 20
+            }
+        ));
+    }
+}
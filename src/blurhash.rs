@@ -0,0 +1,139 @@
+//! A small, self-contained BlurHash encoder (see https://blurha.sh), used to
+//! generate compact placeholder strings for extracted textures so viewers
+//! can show a blurred preview before the full PNG loads.
+
+const BASE83_ALPHABET: &[u8] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+fn encode_base83(mut value: u32, length: usize) -> String {
+    let mut result = vec![0u8; length];
+    for i in (0..length).rev() {
+        let digit = value % 83;
+        result[i] = BASE83_ALPHABET[digit as usize];
+        value /= 83;
+    }
+    String::from_utf8(result).unwrap()
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let v = value as f64 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> u8 {
+    let v = value.clamp(0.0, 1.0);
+    let srgb = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+fn sign(value: f64) -> f64 {
+    if value < 0.0 {
+        -1.0
+    } else {
+        1.0
+    }
+}
+
+fn encode_dc(r: f64, g: f64, b: f64) -> u32 {
+    ((linear_to_srgb(r) as u32) << 16)
+        | ((linear_to_srgb(g) as u32) << 8)
+        | (linear_to_srgb(b) as u32)
+}
+
+fn encode_ac(r: f64, g: f64, b: f64, max_ac: f64) -> u32 {
+    let quantize = |v: f64| -> u32 {
+        (sign(v) * (v.abs() / max_ac).powf(0.5) * 9.0 + 9.5)
+            .floor()
+            .clamp(0.0, 18.0) as u32
+    };
+    quantize(r) * 19 * 19 + quantize(g) * 19 + quantize(b)
+}
+
+/// Computes one `(r, g, b)` DCT basis factor over an RGBA image.
+fn basis_factor(rgba: &[u8], width: u32, height: u32, i: u32, j: u32) -> (f64, f64, f64) {
+    let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+    let mut r = 0.0;
+    let mut g = 0.0;
+    let mut b = 0.0;
+    for y in 0..height {
+        for x in 0..width {
+            let basis = (std::f64::consts::PI * i as f64 * x as f64 / width as f64).cos()
+                * (std::f64::consts::PI * j as f64 * y as f64 / height as f64).cos();
+            let pi = 4 * (y * width + x) as usize;
+            r += basis * srgb_to_linear(rgba[pi]);
+            g += basis * srgb_to_linear(rgba[pi + 1]);
+            b += basis * srgb_to_linear(rgba[pi + 2]);
+        }
+    }
+    let scale = normalisation / (width * height) as f64;
+    (r * scale, g * scale, b * scale)
+}
+
+/// Encodes `rgba` (row-major, 4 bytes/pixel) into a BlurHash string.
+pub fn encode(rgba: &[u8], width: u32, height: u32, components_x: u32, components_y: u32) -> String {
+    assert!((1..=9).contains(&components_x));
+    assert!((1..=9).contains(&components_y));
+
+    let mut factors = Vec::with_capacity((components_x * components_y) as usize);
+    for j in 0..components_y {
+        for i in 0..components_x {
+            factors.push(basis_factor(rgba, width, height, i, j));
+        }
+    }
+
+    let dc = factors[0];
+    let ac = &factors[1..];
+
+    let mut result = String::new();
+    result.push_str(&encode_base83(
+        (components_x - 1) + (components_y - 1) * 9,
+        1,
+    ));
+
+    let max_ac = if ac.is_empty() {
+        None
+    } else {
+        let max_ac = ac
+            .iter()
+            .flat_map(|&(r, g, b)| [r.abs(), g.abs(), b.abs()])
+            .fold(0.0f64, f64::max);
+        let quantized = ((max_ac * 166.0 - 0.5).floor().clamp(0.0, 82.0)) as u32;
+        result.push_str(&encode_base83(quantized, 1));
+        Some(((quantized + 1) as f64) / 166.0)
+    };
+    if max_ac.is_none() {
+        result.push_str(&encode_base83(0, 1));
+    }
+
+    result.push_str(&encode_base83(encode_dc(dc.0, dc.1, dc.2), 4));
+    if let Some(max_ac) = max_ac {
+        for &(r, g, b) in ac {
+            result.push_str(&encode_base83(encode_ac(r, g, b, max_ac), 2));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_base83_matches_a_known_vector() {
+        assert_eq!(encode_base83(16777215, 4), "TSUA");
+    }
+
+    #[test]
+    fn encode_a_single_white_pixel_with_no_ac_components() {
+        let hash = encode(&[255, 255, 255, 255], 1, 1, 1, 1);
+        assert_eq!(hash, "00TSUA");
+    }
+}
@@ -1,5 +1,11 @@
 use binrw::{BinRead, BinWrite};
 
+#[derive(Debug, thiserror::Error)]
+pub enum DecompressError {
+    #[error("texture data length mismatch: expected {expected} bytes, got {actual}")]
+    DataLengthMismatch { expected: usize, actual: usize },
+}
+
 #[repr(C)]
 #[derive(BinRead, BinWrite)]
 #[brw(little)]
@@ -26,10 +32,13 @@ pub struct Texture {
     pub data_count_1: u32,
     #[br(count = data_count_0)]
     pub data: Vec<u8>,
+    /// Second data buffer; see [`decompress_secondary`].
+    #[br(count = data_count_1)]
+    pub data_1: Vec<u8>,
 }
 
 #[repr(u32)]
-#[derive(BinRead, BinWrite)]
+#[derive(BinRead, BinWrite, Clone, Copy)]
 #[brw(little, repr(u32))]
 pub enum TextureFormat {
     RGB8A1 = 1,
@@ -38,7 +47,7 @@ pub enum TextureFormat {
 }
 
 #[repr(C)]
-#[derive(BinRead, BinWrite)]
+#[derive(BinRead, BinWrite, Clone, Copy, Default)]
 #[brw(little)]
 pub struct Rgb {
     pub r: i16,
@@ -177,21 +186,460 @@ fn decompress_rgb8a1(
     result
 }
 
-pub fn decompress(texture: &Texture) -> anyhow::Result<Vec<u8>, ()> {
-    let properties = TextureProperties::from_texture(texture);
+fn decompress_data(
+    data: &[u8],
+    format: &TextureFormat,
+    properties: &TextureProperties,
+    brightness: &[u8; 16],
+    rgb_0: &[Rgb; 4],
+    rgb_1: &[Rgb; 4],
+) -> Result<Vec<u8>, DecompressError> {
     let expected_data_length = properties.data_length();
+    if data.len() != expected_data_length {
+        return Err(DecompressError::DataLengthMismatch {
+            expected: expected_data_length,
+            actual: data.len(),
+        });
+    }
+    match format {
+        TextureFormat::R7G6B5A1 => Ok(decompress_r7g6b5a1(data, properties)),
+        TextureFormat::ARGB4 => Ok(decompress_argb4(data, properties)),
+        TextureFormat::RGB8A1 => Ok(decompress_rgb8a1(data, properties, brightness, rgb_0, rgb_1)),
+    }
+}
+
+pub fn decompress(texture: &Texture) -> Result<Vec<u8>, DecompressError> {
+    let properties = TextureProperties::from_texture(texture);
+    decompress_data(
+        &texture.data,
+        &texture.format,
+        &properties,
+        &texture.brightness,
+        &texture.rgb_0,
+        &texture.rgb_1,
+    )
+}
+
+/// Decodes `data_1` the same way [`decompress`] decodes `data`.
+pub fn decompress_secondary(texture: &Texture) -> Result<Vec<u8>, DecompressError> {
+    let properties = TextureProperties::from_texture(texture);
+    decompress_data(
+        &texture.data_1,
+        &texture.format,
+        &properties,
+        &texture.brightness,
+        &texture.rgb_0,
+        &texture.rgb_1,
+    )
+}
+
+/// `(block_width, block_height, bits_per_pixel)` for the tiled layout.
+fn tiled_block_layout(format: &TextureFormat) -> (u32, u32, u32) {
+    match format {
+        TextureFormat::RGB8A1 => (8, 4, 8),
+        TextureFormat::R7G6B5A1 | TextureFormat::ARGB4 => (4, 4, 16),
+    }
+}
+
+fn read_block_pixel(block: &[u8], block_width: u32, px_x: u32, px_y: u32, bits_per_pixel: u32) -> u32 {
+    match bits_per_pixel {
+        4 => {
+            let byte = block[(px_y * block_width / 2 + px_x / 2) as usize];
+            let shift = 4 * ((px_x & 1) ^ 1);
+            ((byte >> shift) & 0xF) as u32
+        }
+        8 => block[(px_y * block_width + px_x) as usize] as u32,
+        16 => {
+            let i = 2 * (px_y * block_width + px_x) as usize;
+            block[i] as u32 | (block[i + 1] as u32) << 8
+        }
+        32 => {
+            let i = 4 * (px_y * block_width + px_x) as usize;
+            u32::from_le_bytes([block[i], block[i + 1], block[i + 2], block[i + 3]])
+        }
+        _ => unreachable!("unsupported tiled bit depth"),
+    }
+}
+
+fn write_linear_pixel(out: &mut [u8], x: u32, y: u32, height: u32, value: u32, bits_per_pixel: u32) {
+    match bits_per_pixel {
+        4 => {
+            let pixel_index = (y + x * height) as usize;
+            let shift = 4 * ((pixel_index & 1) ^ 1);
+            out[pixel_index / 2] |= ((value & 0xF) as u8) << shift;
+        }
+        8 => out[(y + x * height) as usize] = value as u8,
+        16 => {
+            let i = 2 * (y + x * height) as usize;
+            out[i] = (value & 0xFF) as u8;
+            out[i + 1] = ((value >> 8) & 0xFF) as u8;
+        }
+        32 => {
+            let i = 4 * (y + x * height) as usize;
+            out[i..i + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        _ => unreachable!("unsupported tiled bit depth"),
+    }
+}
+
+/// De-swizzles `data` from `block_width x block_height` tiles into the
+/// linear layout the `decompress_*` functions expect.
+fn detile(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    block_width: u32,
+    block_height: u32,
+    bits_per_pixel: u32,
+) -> Vec<u8> {
+    let blocks_x = width.div_ceil(block_width);
+    let blocks_y = height.div_ceil(block_height);
+    let block_bytes = (block_width * block_height * bits_per_pixel / 8) as usize;
+    let out_len = (width as usize * height as usize * bits_per_pixel as usize).div_ceil(8);
+    let mut out = vec![0u8; out_len];
+
+    for block_y in 0..blocks_y {
+        for block_x in 0..blocks_x {
+            let block_index = (block_y * blocks_x + block_x) as usize;
+            let block = &data[block_index * block_bytes..(block_index + 1) * block_bytes];
+            for px_y in 0..block_height {
+                for px_x in 0..block_width {
+                    let x = block_x * block_width + px_x;
+                    let y = block_y * block_height + px_y;
+                    if x >= width || y >= height {
+                        continue;
+                    }
+                    let value = read_block_pixel(block, block_width, px_x, px_y, bits_per_pixel);
+                    write_linear_pixel(&mut out, x, y, height, value, bits_per_pixel);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Alternate to [`decompress`] for textures stored in block-swizzled
+/// (tiled) layout.
+pub fn decompress_tiled(texture: &Texture) -> Result<Vec<u8>, DecompressError> {
+    let properties = TextureProperties::from_texture(texture);
+    let (block_width, block_height, bits_per_pixel) = tiled_block_layout(&texture.format);
+    let blocks_x = properties.width.div_ceil(block_width);
+    let blocks_y = properties.height.div_ceil(block_height);
+    let block_bytes = (block_width * block_height * bits_per_pixel / 8) as usize;
+    let expected_data_length = blocks_x as usize * blocks_y as usize * block_bytes;
     if texture.data.len() != expected_data_length {
-        return Err(()); // TODO: Return a reasonable error here
+        return Err(DecompressError::DataLengthMismatch {
+            expected: expected_data_length,
+            actual: texture.data.len(),
+        });
     }
+
+    let linear = detile(
+        &texture.data,
+        properties.width,
+        properties.height,
+        block_width,
+        block_height,
+        bits_per_pixel,
+    );
     match texture.format {
-        TextureFormat::R7G6B5A1 => Ok(decompress_r7g6b5a1(&texture.data, &properties)),
-        TextureFormat::ARGB4 => Ok(decompress_argb4(&texture.data, &properties)),
+        TextureFormat::R7G6B5A1 => Ok(decompress_r7g6b5a1(&linear, &properties)),
+        TextureFormat::ARGB4 => Ok(decompress_argb4(&linear, &properties)),
         TextureFormat::RGB8A1 => Ok(decompress_rgb8a1(
-            &texture.data,
+            &linear,
             &properties,
             &texture.brightness,
             &texture.rgb_0,
-            &texture.rgb_1
+            &texture.rgb_1,
         )),
     }
 }
+
+/// The on-disk fields a call to [`compress`] needs to fill in alongside the
+/// pixel data itself; non-`RGB8A1` formats leave the palette fields zeroed.
+pub struct CompressedTexture {
+    pub data: Vec<u8>,
+    pub brightness: [u8; 16],
+    pub rgb_0: [Rgb; 4],
+    pub rgb_1: [Rgb; 4],
+}
+
+fn compress_r7g6b5a1(rgba: &[u8], properties: &TextureProperties) -> Vec<u8> {
+    let mut result = vec![0u8; properties.data_length()];
+    for x in 0..properties.width {
+        for y in 0..properties.height {
+            let pi = 4 * (y + x * properties.height) as usize;
+            let r = (rgba[pi] >> 3) as u32;
+            let g = (rgba[pi + 1] >> 3) as u32;
+            let mut b = (rgba[pi + 2] >> 3) as u32;
+            let a = if rgba[pi + 3] == 0 { 0 } else { 0x8000 };
+            // p == 0x8000 (opaque black) decodes as transparent, so nudge
+            // opaque black pixels off zero to preserve their opacity.
+            if a != 0 && r == 0 && g == 0 && b == 0 {
+                b = 1;
+            }
+            let p = a | (r << 10) | (g << 5) | b;
+            let i = 2 * (y + x * properties.height) as usize;
+            result[i] = (p & 0xFF) as u8;
+            result[i + 1] = ((p >> 8) & 0xFF) as u8;
+        }
+    }
+    result
+}
+
+fn compress_argb4(rgba: &[u8], properties: &TextureProperties) -> Vec<u8> {
+    let mut result = vec![0u8; properties.data_length()];
+    for x in 0..properties.width {
+        for y in 0..properties.height {
+            let pi = 4 * (y + x * properties.height) as usize;
+            let r = (rgba[pi] >> 4) as u32;
+            let g = (rgba[pi + 1] & 0xF0) as u32;
+            let b = (rgba[pi + 2] >> 4) as u32;
+            let a = (rgba[pi + 3] >> 4) as u32;
+            let p = (a << 12) | (r << 8) | g | b;
+            let i = 2 * (y + x * properties.height) as usize;
+            result[i] = (p & 0xFF) as u8;
+            result[i + 1] = ((p >> 8) & 0xFF) as u8;
+        }
+    }
+    result
+}
+
+/// Refinement passes for fitting the `RGB8A1` luma/chroma tables.
+const RGB8A1_KMEANS_ITERATIONS: usize = 6;
+
+/// Fits the shared luma table and the two 4-entry chroma palettes used by
+/// `RGB8A1`, then re-quantizes every pixel against them. Entry `0` of each
+/// table is held at zero and never refitted, so fully transparent pixels are
+/// written as byte `0`; opaque pixels may also select entry `0` of any table.
+fn compress_rgb8a1(
+    rgba: &[u8],
+    properties: &TextureProperties,
+) -> (Vec<u8>, [u8; 16], [Rgb; 4], [Rgb; 4]) {
+    let pixel_count = properties.pixel_count();
+    let mut opaque = Vec::with_capacity(pixel_count);
+    for i in 0..pixel_count {
+        let pi = 4 * i;
+        if rgba[pi + 3] != 0 {
+            opaque.push((rgba[pi] as i32, rgba[pi + 1] as i32, rgba[pi + 2] as i32));
+        }
+    }
+
+    let mut brightness = [0i32; 16];
+    let mut rgb_0 = [(0i32, 0i32, 0i32); 4];
+    let mut rgb_1 = [(0i32, 0i32, 0i32); 4];
+    if !opaque.is_empty() {
+        for k in 1..16 {
+            brightness[k] = (255 * k / 15) as i32;
+        }
+        for k in 1..4 {
+            let offset = -96 + (k as i32) * 64;
+            rgb_0[k] = (offset, offset, offset);
+            rgb_1[k] = (-offset, -offset, -offset);
+        }
+
+        for _ in 0..RGB8A1_KMEANS_ITERATIONS {
+            let mut l_sum = [(0i64, 0i64, 0i64, 0u64); 16];
+            let mut c0_sum = [(0i64, 0i64, 0i64, 0u64); 4];
+            let mut c1_sum = [(0i64, 0i64, 0i64, 0u64); 4];
+
+            for &(r, g, b) in &opaque {
+                let (l_idx, i0, i1) = best_rgb8a1_combo((r, g, b), &brightness, &rgb_0, &rgb_1);
+                let l = brightness[l_idx];
+                let c0 = rgb_0[i0];
+                let c1 = rgb_1[i1];
+
+                let acc = &mut l_sum[l_idx];
+                acc.0 += (r - c0.0 - c1.0) as i64;
+                acc.1 += (g - c0.1 - c1.1) as i64;
+                acc.2 += (b - c0.2 - c1.2) as i64;
+                acc.3 += 1;
+
+                if i0 != 0 {
+                    let acc = &mut c0_sum[i0];
+                    acc.0 += (r - l - c1.0) as i64;
+                    acc.1 += (g - l - c1.1) as i64;
+                    acc.2 += (b - l - c1.2) as i64;
+                    acc.3 += 1;
+                }
+                if i1 != 0 {
+                    let acc = &mut c1_sum[i1];
+                    acc.0 += (r - l - c0.0) as i64;
+                    acc.1 += (g - l - c0.1) as i64;
+                    acc.2 += (b - l - c0.2) as i64;
+                    acc.3 += 1;
+                }
+            }
+
+            for k in 1..16 {
+                let (sr, sg, sb, n) = l_sum[k];
+                if n > 0 {
+                    brightness[k] = (((sr + sg + sb) / (3 * n as i64)) as i32).clamp(0, 255);
+                }
+            }
+            for k in 1..4 {
+                let (sr, sg, sb, n) = c0_sum[k];
+                if n > 0 {
+                    rgb_0[k] = (
+                        ((sr / n as i64) as i32).clamp(-256, 255),
+                        ((sg / n as i64) as i32).clamp(-256, 255),
+                        ((sb / n as i64) as i32).clamp(-256, 255),
+                    );
+                }
+                let (sr, sg, sb, n) = c1_sum[k];
+                if n > 0 {
+                    rgb_1[k] = (
+                        ((sr / n as i64) as i32).clamp(-256, 255),
+                        ((sg / n as i64) as i32).clamp(-256, 255),
+                        ((sb / n as i64) as i32).clamp(-256, 255),
+                    );
+                }
+            }
+        }
+    }
+
+    let mut data = vec![0u8; properties.pixel_count()];
+    for x in 0..properties.width {
+        for y in 0..properties.height {
+            let pixel = (x * properties.height + y) as usize;
+            let i = (y + x * properties.height) as usize;
+            let pi = 4 * pixel;
+            if rgba[pi + 3] == 0 {
+                data[i] = 0;
+                continue;
+            }
+            let rgb = (rgba[pi] as i32, rgba[pi + 1] as i32, rgba[pi + 2] as i32);
+            let (l_idx, i0, i1) = best_rgb8a1_combo(rgb, &brightness, &rgb_0, &rgb_1);
+            data[i] = ((l_idx as u8) << 4) | ((i0 as u8) << 2) | (i1 as u8);
+        }
+    }
+    // Mirror decompress_rgb8a1's first-pixel overwrite quirk
+    for i in 1..data.len().min(3) {
+        data[i] = data[0];
+    }
+
+    let to_rgb = |(r, g, b): (i32, i32, i32)| Rgb {
+        r: r as i16,
+        g: g as i16,
+        b: b as i16,
+    };
+    let brightness = brightness.map(|v| v as u8);
+    let rgb_0 = rgb_0.map(to_rgb);
+    let rgb_1 = rgb_1.map(to_rgb);
+    (data, brightness, rgb_0, rgb_1)
+}
+
+/// Finds the `(luma, chroma_0, chroma_1)` index combination that minimizes
+/// squared error against `rgb`, by brute force over all `16 * 4 * 4` combos.
+fn best_rgb8a1_combo(
+    rgb: (i32, i32, i32),
+    brightness: &[i32; 16],
+    rgb_0: &[(i32, i32, i32); 4],
+    rgb_1: &[(i32, i32, i32); 4],
+) -> (usize, usize, usize) {
+    let (r, g, b) = rgb;
+    let mut best = (0usize, 0usize, 0usize);
+    let mut best_error = i64::MAX;
+    for (l_idx, &l) in brightness.iter().enumerate() {
+        for (i0, &(r0, g0, b0)) in rgb_0.iter().enumerate() {
+            for (i1, &(r1, g1, b1)) in rgb_1.iter().enumerate() {
+                let dr = (l + r0 + r1 - r) as i64;
+                let dg = (l + g0 + g1 - g) as i64;
+                let db = (l + b0 + b1 - b) as i64;
+                let error = dr * dr + dg * dg + db * db;
+                if error < best_error {
+                    best_error = error;
+                    best = (l_idx, i0, i1);
+                }
+            }
+        }
+    }
+    best
+}
+
+/// Inverse of [`decompress`]: quantizes RGBA pixels back into the on-disk
+/// representation for `format`.
+pub fn compress(
+    rgba: &[u8],
+    format: TextureFormat,
+    properties: &TextureProperties,
+) -> CompressedTexture {
+    match format {
+        TextureFormat::R7G6B5A1 => CompressedTexture {
+            data: compress_r7g6b5a1(rgba, properties),
+            brightness: [0; 16],
+            rgb_0: [Rgb::default(); 4],
+            rgb_1: [Rgb::default(); 4],
+        },
+        TextureFormat::ARGB4 => CompressedTexture {
+            data: compress_argb4(rgba, properties),
+            brightness: [0; 16],
+            rgb_0: [Rgb::default(); 4],
+            rgb_1: [Rgb::default(); 4],
+        },
+        TextureFormat::RGB8A1 => {
+            let (data, brightness, rgb_0, rgb_1) = compress_rgb8a1(rgba, properties);
+            CompressedTexture {
+                data,
+                brightness,
+                rgb_0,
+                rgb_1,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard(width: u32, height: u32, colors: &[(u8, u8, u8, u8); 2]) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+        for x in 0..width {
+            for y in 0..height {
+                let (r, g, b, a) = colors[((x + y) % 2) as usize];
+                rgba.extend_from_slice(&[r, g, b, a]);
+            }
+        }
+        rgba
+    }
+
+    #[test]
+    fn r7g6b5a1_round_trips_through_compress_and_decompress() {
+        let properties = TextureProperties {
+            width: 4,
+            height: 4,
+            stride: 2,
+        };
+        let rgba = checkerboard(4, 4, &[(0x08, 0x90, 0xF8, 0xFF), (0xF8, 0x00, 0x58, 0xFF)]);
+        let data = compress_r7g6b5a1(&rgba, &properties);
+        assert_eq!(decompress_r7g6b5a1(&data, &properties), rgba);
+    }
+
+    #[test]
+    fn r7g6b5a1_opaque_black_stays_opaque() {
+        let properties = TextureProperties {
+            width: 4,
+            height: 4,
+            stride: 2,
+        };
+        let rgba = checkerboard(4, 4, &[(0, 0, 0, 0xFF), (0, 0, 0, 0xFF)]);
+        let data = compress_r7g6b5a1(&rgba, &properties);
+        let decoded = decompress_r7g6b5a1(&data, &properties);
+        for pixel in decoded.chunks_exact(4) {
+            assert_eq!(pixel[3], 0xFF);
+        }
+    }
+
+    #[test]
+    fn argb4_round_trips_through_compress_and_decompress() {
+        let properties = TextureProperties {
+            width: 4,
+            height: 4,
+            stride: 2,
+        };
+        let rgba = checkerboard(4, 4, &[(0x10, 0x90, 0xF0, 0xF0), (0xF0, 0x00, 0x50, 0x00)]);
+        let data = compress_argb4(&rgba, &properties);
+        assert_eq!(decompress_argb4(&data, &properties), rgba);
+    }
+}
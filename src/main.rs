@@ -1,48 +1,300 @@
+pub mod atlas;
+pub mod blurhash;
 pub mod gex;
 
-use std::{env, fs::{File, create_dir_all}, io::BufReader, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{File, create_dir_all},
+    io::Cursor,
+    path::{Path, PathBuf},
+};
 
-use binrw::BinReaderExt;
+use anyhow::Context;
+use binrw::{BinReaderExt, BinWriterExt};
 use gex::vfx;
+use memmap2::Mmap;
+use rayon::prelude::*;
+use serde::Serialize;
 
-fn extract_vfx(path: &PathBuf) {
-    let file = File::open(path).unwrap();
-    let vfx: vfx::File = BufReader::new(file).read_le().unwrap();
-    let level_name = path.file_stem().unwrap();
-    let output_path = path.parent().unwrap().to_path_buf().join(level_name);
-    create_dir_all(&output_path).unwrap();
-    for (index, texture) in vfx.textures.iter().enumerate() {
-        let properties = vfx::TextureProperties::from_texture(texture);
-        let buffer = vfx::decompress(texture).unwrap();
-        let format = match texture.format {
-            vfx::TextureFormat::RGB8A1 => "rgb8a1",
-            vfx::TextureFormat::R7G6B5A1 => "r7g6b5a1",
-            vfx::TextureFormat::ARGB4 => "argb4",
+fn format_name(format: &vfx::TextureFormat) -> &'static str {
+    match format {
+        vfx::TextureFormat::RGB8A1 => "rgb8a1",
+        vfx::TextureFormat::R7G6B5A1 => "r7g6b5a1",
+        vfx::TextureFormat::ARGB4 => "argb4",
+    }
+}
+
+/// BlurHash component counts used for extracted texture placeholders.
+const BLURHASH_COMPONENTS_X: u32 = 4;
+const BLURHASH_COMPONENTS_Y: u32 = 3;
+
+/// How `extract_texture` should treat each `Texture`'s second data buffer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SecondarySurfaceMode {
+    /// Leave it unexamined (the historical behavior).
+    Skip,
+    /// Decode it and save it as its own `{index}_{format}_1.png`.
+    Separate,
+    /// Decode it and save it as `{index}_{format}_mip1.png`.
+    Mipmap,
+}
+
+fn extract_texture(
+    texture: &vfx::Texture,
+    index: usize,
+    output_dir: &Path,
+    tiled: bool,
+    secondary: SecondarySurfaceMode,
+) -> anyhow::Result<()> {
+    let properties = vfx::TextureProperties::from_texture(texture);
+    let buffer = if tiled {
+        vfx::decompress_tiled(texture)?
+    } else {
+        vfx::decompress(texture)?
+    };
+    let format = format_name(&texture.format);
+    let output_path = output_dir.join(format!("{index}_{format}.png"));
+    println!("{output_path:?}");
+    image::save_buffer(
+        &output_path,
+        &buffer,
+        properties.width,
+        properties.height,
+        image::ColorType::Rgba8,
+    )?;
+
+    let hash = blurhash::encode(
+        &buffer,
+        properties.width,
+        properties.height,
+        BLURHASH_COMPONENTS_X,
+        BLURHASH_COMPONENTS_Y,
+    );
+    std::fs::write(output_path.with_extension("blurhash"), hash)?;
+
+    if secondary != SecondarySurfaceMode::Skip {
+        let suffix = match secondary {
+            SecondarySurfaceMode::Separate => "_1",
+            SecondarySurfaceMode::Mipmap => "_mip1",
+            SecondarySurfaceMode::Skip => unreachable!(),
         };
-        let output_path = output_path.join(format!("{index}_{format}.png"));
-        println!("{output_path:?}");
+        let secondary_buffer = vfx::decompress_secondary(texture)?;
+        let secondary_path = output_dir.join(format!("{index}_{format}{suffix}.png"));
         image::save_buffer(
-            &output_path,
-            &buffer,
+            &secondary_path,
+            &secondary_buffer,
             properties.width,
             properties.height,
             image::ColorType::Rgba8,
-        )
-        .unwrap();
+        )?;
     }
+
+    Ok(())
 }
 
-fn main() {
-    let paths: Vec<PathBuf> = env::args()
-        .map(PathBuf::from)
-        .filter(|x| x.exists() && x.is_file())
-        .collect();
+/// Extracts every texture in `path` in parallel; a malformed texture reports
+/// a diagnostic on stderr rather than aborting the batch.
+fn extract_vfx(path: &PathBuf, tiled: bool, secondary: SecondarySurfaceMode) -> anyhow::Result<()> {
+    let file = File::open(path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let mut cursor = Cursor::new(&mmap[..]);
+    let vfx: vfx::File = cursor.read_le()?;
 
-    for filepath in &paths {
-        if let Some(os_str) = filepath.extension() {
-            if let Some("vfx") = os_str.to_str() {
-                extract_vfx(filepath);
+    let level_name = path.file_stem().context("path has no file stem")?;
+    let output_dir = path.parent().context("path has no parent directory")?.join(level_name);
+    create_dir_all(&output_dir)?;
+
+    vfx.textures
+        .par_iter()
+        .enumerate()
+        .for_each(|(index, texture)| {
+            if let Err(error) = extract_texture(texture, index, &output_dir, tiled, secondary) {
+                eprintln!("{path:?} texture {index}: {error}");
             }
+        });
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct AtlasEntry {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    format: &'static str,
+}
+
+/// Packs every texture in `path` into a single atlas PNG plus a sidecar
+/// `{level}_atlas.json` manifest.
+fn atlas_vfx(path: &PathBuf, atlas_width: u32, tiled: bool) -> anyhow::Result<()> {
+    let file = File::open(path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let mut cursor = Cursor::new(&mmap[..]);
+    let vfx: vfx::File = cursor.read_le()?;
+
+    let mut buffers = Vec::with_capacity(vfx.textures.len());
+    let mut sizes = Vec::with_capacity(vfx.textures.len());
+    for (index, texture) in vfx.textures.iter().enumerate() {
+        let properties = vfx::TextureProperties::from_texture(texture);
+        let decoded = if tiled {
+            vfx::decompress_tiled(texture)
+        } else {
+            vfx::decompress(texture)
+        };
+        match decoded {
+            Ok(buffer) => {
+                sizes.push((properties.width, properties.height));
+                buffers.push((index, buffer));
+            }
+            Err(error) => eprintln!("{path:?} texture {index}: {error}"),
+        }
+    }
+
+    let (rects, atlas_height) = atlas::pack(&sizes, atlas_width)?;
+
+    let mut image = image::RgbaImage::new(atlas_width, atlas_height);
+    let mut manifest = BTreeMap::new();
+    for (rect_index, (index, buffer)) in buffers.into_iter().enumerate() {
+        let rect = rects[rect_index];
+        let texture = &vfx.textures[index];
+        let sub = image::RgbaImage::from_raw(rect.width, rect.height, buffer)
+            .context("decompressed buffer did not match its own texture dimensions")?;
+        image::imageops::overlay(&mut image, &sub, rect.x as i64, rect.y as i64);
+        manifest.insert(
+            index,
+            AtlasEntry {
+                x: rect.x,
+                y: rect.y,
+                width: rect.width,
+                height: rect.height,
+                format: format_name(&texture.format),
+            },
+        );
+    }
+
+    let level_name = path.file_stem().context("path has no file stem")?;
+    let output_dir = path.parent().context("path has no parent directory")?;
+    let atlas_path = output_dir.join(format!("{}_atlas.png", level_name.to_string_lossy()));
+    let manifest_path = output_dir.join(format!("{}_atlas.json", level_name.to_string_lossy()));
+    image.save(&atlas_path)?;
+    std::fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+    println!("{atlas_path:?}");
+    println!("{manifest_path:?}");
+
+    Ok(())
+}
+
+/// Inverse of [`extract_vfx`]: re-quantizes the PNGs it wrote under
+/// `textures_dir` and writes a new `{level}_repacked.vfx` next to the original.
+fn repack_vfx(original_path: &PathBuf, textures_dir: &PathBuf) -> anyhow::Result<()> {
+    let file = File::open(original_path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    let mut cursor = Cursor::new(&mmap[..]);
+    let mut vfx: vfx::File = cursor.read_le()?;
+
+    for (index, texture) in vfx.textures.iter_mut().enumerate() {
+        let properties = vfx::TextureProperties::from_texture(texture);
+        let format = format_name(&texture.format);
+        let png_path = textures_dir.join(format!("{index}_{format}.png"));
+        let image = image::open(&png_path)
+            .with_context(|| format!("failed to open {png_path:?}"))?
+            .to_rgba8();
+        let compressed = vfx::compress(image.as_raw(), texture.format, &properties);
+        texture.data_count_0 = compressed.data.len() as u32;
+        texture.data = compressed.data;
+
+        let separate_path = textures_dir.join(format!("{index}_{format}_1.png"));
+        let mipmap_path = textures_dir.join(format!("{index}_{format}_mip1.png"));
+        let secondary_path = [&separate_path, &mipmap_path].into_iter().find(|path| path.exists());
+        if let Some(secondary_path) = secondary_path {
+            let secondary_image = image::open(secondary_path)
+                .with_context(|| format!("failed to open {secondary_path:?}"))?
+                .to_rgba8();
+            let secondary_compressed = vfx::compress(secondary_image.as_raw(), texture.format, &properties);
+            texture.data_1 = secondary_compressed.data;
+        }
+        texture.data_count_1 = texture.data_1.len() as u32;
+        if let vfx::TextureFormat::RGB8A1 = texture.format {
+            texture.brightness = compressed.brightness;
+            texture.rgb_0 = compressed.rgb_0;
+            texture.rgb_1 = compressed.rgb_1;
+        }
+    }
+
+    let level_name = original_path
+        .file_stem()
+        .context("path has no file stem")?
+        .to_string_lossy()
+        .into_owned();
+    let output_path = original_path
+        .parent()
+        .context("path has no parent directory")?
+        .join(format!("{level_name}_repacked.vfx"));
+    let mut writer = File::create(&output_path)?;
+    writer.write_le(&vfx)?;
+    println!("{output_path:?}");
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if let [_, flag, original, textures_dir] = args.as_slice() {
+        if flag == "--repack" {
+            return repack_vfx(&PathBuf::from(original), &PathBuf::from(textures_dir));
         }
     }
+    if let [_, flag, width, rest @ ..] = args.as_slice() {
+        if flag == "--atlas" {
+            let atlas_width: u32 = width.parse().context("--atlas width must be a positive integer")?;
+            let tiled = rest.iter().any(|arg| arg == "--tiled");
+            let paths: Vec<PathBuf> = rest
+                .iter()
+                .filter(|arg| arg.as_str() != "--tiled")
+                .map(PathBuf::from)
+                .filter(|x| x.exists() && x.is_file())
+                .filter(|x| x.extension().and_then(|ext| ext.to_str()) == Some("vfx"))
+                .collect();
+            paths.par_iter().for_each(|filepath| {
+                if let Err(error) = atlas_vfx(filepath, atlas_width, tiled) {
+                    eprintln!("{filepath:?}: {error}");
+                }
+            });
+            return Ok(());
+        }
+    }
+
+    let tiled = args[1..].iter().any(|arg| arg == "--tiled");
+
+    let secondary = match args[1..].iter().position(|arg| arg == "--secondary") {
+        Some(position) => match args.get(position + 2).map(String::as_str) {
+            Some("separate") => SecondarySurfaceMode::Separate,
+            Some("mipmap") => SecondarySurfaceMode::Mipmap,
+            Some("skip") => SecondarySurfaceMode::Skip,
+            other => anyhow::bail!("--secondary expects mipmap|separate|skip, got {other:?}"),
+        },
+        None => SecondarySurfaceMode::Skip,
+    };
+
+    let paths: Vec<PathBuf> = args[1..]
+        .iter()
+        .enumerate()
+        .filter(|&(i, arg)| {
+            let arg = arg.as_str();
+            let preceded_by_secondary = args.get(i).map(String::as_str) == Some("--secondary");
+            arg != "--tiled" && arg != "--secondary" && !preceded_by_secondary
+        })
+        .map(|(_, arg)| PathBuf::from(arg))
+        .filter(|x| x.exists() && x.is_file())
+        .filter(|x| x.extension().and_then(|ext| ext.to_str()) == Some("vfx"))
+        .collect();
+
+    paths.par_iter().for_each(|filepath| {
+        if let Err(error) = extract_vfx(filepath, tiled, secondary) {
+            eprintln!("{filepath:?}: {error}");
+        }
+    });
+
+    Ok(())
 }